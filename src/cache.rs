@@ -0,0 +1,126 @@
+#![forbid(unsafe_code)]
+use crate::config::CacheConfig;
+use axum::http::HeaderMap;
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Identifies a cached, already-processed image by the inputs that determine its
+/// bytes: the resolved upstream URL, the requested geometry and the target MIME.
+#[derive(Debug, Clone, Hash, Eq, PartialEq)]
+pub struct CacheKey(u64);
+
+impl CacheKey {
+    pub fn new(upstream_url: &str, geometry: Option<(u32, u32)>, target_mime: &str) -> Self {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        upstream_url.hash(&mut hasher);
+        geometry.hash(&mut hasher);
+        target_mime.hash(&mut hasher);
+        CacheKey(hasher.finish())
+    }
+}
+
+/// Revalidation data taken from the upstream response, reused on the next request
+/// to issue a conditional GET instead of a full re-fetch.
+#[derive(Debug, Clone, Default)]
+pub struct Validators {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Validators {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        Validators {
+            etag: headers
+                .get(axum::http::header::ETAG)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+            last_modified: headers
+                .get(axum::http::header::LAST_MODIFIED)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub body: Vec<u8>,
+    pub headers: HeaderMap,
+    pub validators: Validators,
+    stored_at: Instant,
+}
+
+impl CacheEntry {
+    pub fn new(body: Vec<u8>, headers: HeaderMap, validators: Validators) -> Self {
+        CacheEntry { body, headers, validators, stored_at: Instant::now() }
+    }
+
+    /// Resets the freshness clock without touching the stored bytes, used after an
+    /// upstream `304 Not Modified` response.
+    pub fn touch(&mut self) {
+        self.stored_at = Instant::now();
+    }
+}
+
+/// A bounded, in-memory cache of processed images keyed on `(upstream URL,
+/// geometry, target MIME)`. Entries are evicted oldest-first once `max_entries`
+/// or `max_bytes` is exceeded, and considered stale after `ttl`.
+pub struct Cache {
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+    max_entries: usize,
+    max_bytes: usize,
+    ttl: Duration,
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        Cache {
+            entries: Mutex::new(HashMap::new()),
+            max_entries: config.max_entries,
+            max_bytes: config.max_bytes,
+            ttl: Duration::from_secs(config.ttl_secs),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    pub fn get(&self, key: &CacheKey) -> Option<CacheEntry> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    pub fn is_fresh(&self, entry: &CacheEntry) -> bool {
+        entry.stored_at.elapsed() < self.ttl
+    }
+
+    pub fn touch(&self, key: &CacheKey) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(key) {
+            entry.touch();
+        }
+    }
+
+    pub fn insert(&self, key: CacheKey, entry: CacheEntry) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert(key, entry);
+        Self::evict(&mut entries, self.max_entries, self.max_bytes);
+    }
+
+    fn evict(entries: &mut HashMap<CacheKey, CacheEntry>, max_entries: usize, max_bytes: usize) {
+        let mut total_bytes: usize = entries.values().map(|e| e.body.len()).sum();
+        while entries.len() > max_entries || total_bytes > max_bytes {
+            let Some(oldest_key) =
+                entries.iter().min_by_key(|(_, e)| e.stored_at).map(|(k, _)| k.clone())
+            else {
+                break;
+            };
+            if let Some(removed) = entries.remove(&oldest_key) {
+                total_bytes -= removed.body.len();
+            }
+        }
+    }
+}
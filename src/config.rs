@@ -13,6 +13,110 @@ const ENV_CONFIG_VAR: &str = "RESIZEIMG_CFG";
 pub struct Upstreams {
     pub path: String,
     pub upstream: String,
+    /// Seek point (in seconds) used by the `Ffmpeg` engine when thumbnailing
+    /// video matched by this route. Defaults to 10% into the clip, floored at
+    /// one second, when unset.
+    pub video_seek_secs: Option<f64>,
+    /// Per-route encoder/resize overrides, merged over `Config::default_encode`.
+    #[serde(default)]
+    pub encode: EncodeOptions,
+}
+
+/// Resize filter choice, mirroring `image::imageops::FilterType`.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+}
+
+/// Encoder and resize knobs that were previously hardcoded. Every field is
+/// optional: an unset field falls back to `Config::default_encode`, and an
+/// unset field there falls back to the resizer's built-in default.
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct EncodeOptions {
+    pub jpeg_quality: Option<u8>,
+    pub png_compression_level: Option<u8>,
+    pub avif_speed: Option<u8>,
+    pub avif_quality: Option<u8>,
+    pub webp_lossless: Option<bool>,
+    pub resize_filter: Option<ResizeFilter>,
+    /// Target MIME types (e.g. `"image/avif"`) this route is allowed to
+    /// produce. Unset permits any format the client negotiates.
+    pub allowed_formats: Option<Vec<String>>,
+}
+
+impl EncodeOptions {
+    /// Fills any field left unset here from `fallback`.
+    pub fn merge(&self, fallback: &EncodeOptions) -> EncodeOptions {
+        EncodeOptions {
+            jpeg_quality: self.jpeg_quality.or(fallback.jpeg_quality),
+            png_compression_level: self.png_compression_level.or(fallback.png_compression_level),
+            avif_speed: self.avif_speed.or(fallback.avif_speed),
+            avif_quality: self.avif_quality.or(fallback.avif_quality),
+            webp_lossless: self.webp_lossless.or(fallback.webp_lossless),
+            resize_filter: self.resize_filter.or(fallback.resize_filter),
+            allowed_formats: self.allowed_formats.clone().or_else(|| fallback.allowed_formats.clone()),
+        }
+    }
+
+    pub fn is_format_allowed(&self, mime_type: &str) -> bool {
+        match &self.allowed_formats {
+            Some(allowed) => allowed.iter().any(|m| m == mime_type),
+            None => true,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TracingConfig {
+    /// OTLP/gRPC collector endpoint, e.g. `http://localhost:4317`. Tracing stays
+    /// log-only (no span export) when this is unset.
+    pub otlp_endpoint: Option<String>,
+    /// Overrides the `service.name` resource attribute attached to exported spans.
+    pub service_name: Option<String>,
+}
+
+fn default_cache_max_entries() -> usize {
+    1024
+}
+
+fn default_cache_max_bytes() -> usize {
+    256 * 1024 * 1024
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    300
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CacheConfig {
+    #[serde(default = "default_cache_max_entries")]
+    pub max_entries: usize,
+    #[serde(default = "default_cache_max_bytes")]
+    pub max_bytes: usize,
+    #[serde(default = "default_cache_ttl_secs")]
+    pub ttl_secs: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            max_entries: default_cache_max_entries(),
+            max_bytes: default_cache_max_bytes(),
+            ttl_secs: default_cache_ttl_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Default)]
+pub struct TlsConfig {
+    /// Path to a PEM-encoded certificate (chain).
+    pub cert_path: String,
+    /// Path to the PEM-encoded private key matching `cert_path`.
+    pub key_path: String,
 }
 
 #[derive(Debug, Deserialize, Clone, Default)]
@@ -22,6 +126,17 @@ pub struct Config {
     pub threads: Option<usize>,
     pub engine: EngineType,
     pub upstreams: Vec<Upstreams>,
+    pub tracing: Option<TracingConfig>,
+    /// Enables the in-memory result cache when present; absent disables caching
+    /// entirely so every request is fetched and re-encoded from upstream.
+    pub cache: Option<CacheConfig>,
+    /// Terminates TLS directly (with HTTP/2 over ALPN) when present; absent
+    /// serves plaintext HTTP/1.1, leaving TLS to a reverse proxy.
+    pub tls: Option<TlsConfig>,
+    /// Instance-wide encoder/resize defaults, overridden per-route by
+    /// `Upstreams::encode`.
+    #[serde(default)]
+    pub default_encode: EncodeOptions,
 }
 impl Config {
     pub fn read(path: &Option<OsString>) -> anyhow::Result<Self> {
@@ -1,11 +1,13 @@
 #![forbid(unsafe_code)]
-use crate::config::{Config, Upstreams};
+use crate::cache::{Cache, CacheEntry, CacheKey, Validators};
+use crate::config::{Config, EncodeOptions, Upstreams};
 use crate::image::Image;
+use crate::singleflight::{FlightOutcome, Lead, SingleFlight};
 use axum::{
     body::Body,
     extract::{Request, State},
     http::header::{HeaderValue, ACCEPT},
-    http::StatusCode,
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
     routing::get,
     Router,
@@ -17,31 +19,50 @@ use std::{
     sync::Arc,
     time::Duration,
 };
+use axum_server::tls_rustls::RustlsConfig;
 use tokio::net::TcpListener;
+use tracing::Instrument;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 static APP_USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"),);
 
-type SharedConfig = Arc<Config>;
+struct AppState {
+    config: Config,
+    cache: Option<Cache>,
+    singleflight: SingleFlight,
+}
+
+type SharedState = Arc<AppState>;
 
 pub async fn http_server(config: Config) -> anyhow::Result<()> {
     let address =
         if let Some(addr) = config.listen_address.clone() { addr } else { "0.0.0.0".to_string() };
     let listen_address: Ipv4Addr = address.parse()?;
     let addr = SocketAddr::from((listen_address, config.port.unwrap_or(8080)));
-    info!("Listening on {addr}");
-    let listener = TcpListener::bind(addr).await?;
-    let shared_config = Arc::new(config);
+    let tls = config.tls.clone();
+    let cache = config.cache.clone().map(Cache::new);
+    let shared_state = Arc::new(AppState { config, cache, singleflight: SingleFlight::new() });
     let app =
-        Router::new().route("/health", get(health)).fallback(get(handle)).with_state(shared_config);
-    Ok(axum::serve(listener, app.into_make_service()).await?)
+        Router::new().route("/health", get(health)).fallback(get(handle)).with_state(shared_state);
+
+    if let Some(tls) = tls {
+        info!("Listening on {addr} (TLS)");
+        let rustls_config = RustlsConfig::from_pem_file(&tls.cert_path, &tls.key_path).await?;
+        axum_server::bind_rustls(addr, rustls_config).serve(app.into_make_service()).await?;
+    } else {
+        info!("Listening on {addr}");
+        let listener = TcpListener::bind(addr).await?;
+        axum::serve(listener, app.into_make_service()).await?;
+    }
+    Ok(())
 }
 
 async fn health<'a>() -> Response {
     (StatusCode::OK, "Healthy\n").into_response()
 }
 
-async fn handle(State(shared_config): State<SharedConfig>, req: Request<Body>) -> Response {
-    match process(shared_config, req).await {
+async fn handle(State(state): State<SharedState>, req: Request<Body>) -> Response {
+    match process(state, req).await {
         Ok(r) => r,
         Err(e) => {
             error!("Processing: {e}");
@@ -50,29 +71,132 @@ async fn handle(State(shared_config): State<SharedConfig>, req: Request<Body>) -
     }
 }
 
-async fn process(config: SharedConfig, req: Request<Body>) -> anyhow::Result<Response> {
+async fn process(state: SharedState, req: Request<Body>) -> anyhow::Result<Response> {
+    let parent_cx = crate::telemetry::extract_parent_context(req.headers());
+    let root_span = tracing::info_span!("process", uri = %req.uri());
+    root_span.set_parent(parent_cx);
+    process_traced(state, req).instrument(root_span).await
+}
+
+async fn process_traced(state: SharedState, req: Request<Body>) -> anyhow::Result<Response> {
     // Choose backend_url
-    let upstream_url =
-        if let Ok(Some(url)) = get_upstream(req.uri().path(), config.upstreams.clone()) {
-            url
+    let (upstream_url, video_seek_secs, route_encode) =
+        if let Ok(Some(resolved)) = get_upstream(req.uri().path(), state.config.upstreams.clone()) {
+            resolved
         } else {
             return Ok((StatusCode::NOT_FOUND, "Not found\n").into_response());
         };
+    let encode_options = route_encode.merge(&state.config.default_encode);
     let (request_parts, _) = req.into_parts();
     let desired_geometry =
         extract_geometry(request_parts.uri.query().unwrap_or_default().to_string());
     debug!("Desired size: {:?}", desired_geometry);
-    // Send request to the backend
-    // ToDo: Propagate accept requests from downstream?
+    let target_override = get_target_mime(request_parts.headers.get(ACCEPT).cloned(), &encode_options);
+    let target_mime_key = target_override.map(|f| f.to_mime_type()).unwrap_or("original");
+
+    let flight_key = CacheKey::new(&upstream_url, desired_geometry, target_mime_key);
+    let cache_key = state.cache.as_ref().map(|_| flight_key.clone());
+    let cached = match (&cache_key, &state.cache) {
+        (Some(key), Some(cache)) => cache.get(key),
+        _ => None,
+    };
+    if let Some(entry) = &cached {
+        if state.cache.as_ref().is_some_and(|cache| cache.is_fresh(entry)) {
+            debug!("Cache hit for {upstream_url}");
+            return Ok(entry_to_response(entry.clone()));
+        }
+    }
+
+    // Coalesce concurrent requests for the same (url, geometry, mime): only the
+    // first caller fetches and processes, the rest await its broadcast result.
+    match state.singleflight.join(flight_key.clone()) {
+        Lead::Follower(mut receiver) => {
+            debug!("Joining in-flight request for {upstream_url}");
+            match receiver.recv().await {
+                Ok(FlightOutcome::Success { body, headers }) => Ok(outcome_to_response(&body, headers)),
+                Ok(FlightOutcome::Error(message)) => {
+                    error!("Upstream processing failed for {upstream_url}: {message}");
+                    Ok(Response::builder().status(StatusCode::BAD_GATEWAY).body(Body::empty())?)
+                }
+                Err(_) => {
+                    error!("Lost the in-flight result for {upstream_url}");
+                    Ok(Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body(Body::empty())?)
+                }
+            }
+        }
+        Lead::Leader(guard) => {
+            let outcome = fetch_and_process(FetchCtx {
+                state: &state,
+                upstream_url: &upstream_url,
+                desired_geometry,
+                target_override,
+                encode_options,
+                video_seek_secs,
+                cached,
+                cache_key,
+            })
+            .await;
+            let response = match &outcome {
+                Ok((body, headers)) => outcome_to_response(body, headers.clone()),
+                Err(e) => {
+                    error!("Processing {upstream_url}: {e}");
+                    Response::builder().status(StatusCode::BAD_GATEWAY).body(Body::empty())?
+                }
+            };
+            guard.complete(match outcome {
+                Ok((body, headers)) => FlightOutcome::Success { body: Arc::new(body), headers },
+                Err(e) => FlightOutcome::Error(Arc::from(e.to_string())),
+            });
+            Ok(response)
+        }
+    }
+}
+
+struct FetchCtx<'a> {
+    state: &'a SharedState,
+    upstream_url: &'a str,
+    desired_geometry: Option<(u32, u32)>,
+    target_override: Option<ImageFormat>,
+    encode_options: EncodeOptions,
+    video_seek_secs: Option<f64>,
+    cached: Option<CacheEntry>,
+    cache_key: Option<CacheKey>,
+}
+
+async fn fetch_and_process(ctx: FetchCtx<'_>) -> anyhow::Result<(Vec<u8>, HeaderMap)> {
+    // Send request to the backend, conditionally if we have a stale cached entry
     let http_client = create_http_client()?;
-    let upstream_answer = http_client.get(upstream_url).send().await?;
+    let fetch_span =
+        tracing::info_span!("upstream_fetch", url = %ctx.upstream_url, status = tracing::field::Empty);
+    let upstream_answer = async {
+        let mut headers = reqwest::header::HeaderMap::new();
+        crate::telemetry::inject_context(&mut headers);
+        if let Some(entry) = &ctx.cached {
+            add_revalidation_headers(&mut headers, &entry.validators);
+        }
+        http_client.get(ctx.upstream_url).headers(headers).send().await
+    }
+    .instrument(fetch_span.clone())
+    .await?;
+    fetch_span.record("status", upstream_answer.status().as_u16());
+
+    if upstream_answer.status() == StatusCode::NOT_MODIFIED {
+        if let (Some(key), Some(cache), Some(entry)) = (&ctx.cache_key, &ctx.state.cache, ctx.cached) {
+            debug!("Upstream confirmed not modified, reusing cached image for {}", ctx.upstream_url);
+            cache.touch(key);
+            return Ok((entry.body, entry.headers));
+        }
+    }
     if !upstream_answer.status().is_success() {
-        error!("Backend responded with code {}", upstream_answer.status().as_str());
-        return Ok(Response::builder().status(StatusCode::BAD_GATEWAY).body(Body::empty())?);
+        return Err(anyhow::anyhow!(
+            "Backend responded with code {}",
+            upstream_answer.status().as_str()
+        ));
     }
 
     // Extract upstream headers
     let upstream_headers = convert_headers(upstream_answer.headers().clone())?;
+    let validators = Validators::from_headers(&upstream_headers);
 
     trace!("Upstream headers: {:?}", upstream_headers);
     // Extract response body (image data)
@@ -80,15 +204,53 @@ async fn process(config: SharedConfig, req: Request<Body>) -> anyhow::Result<Res
 
     debug!("Image download complete");
     // Create an image object with the response
-    let mut image = Image::new(payload, upstream_headers, desired_geometry, config.engine.clone())?;
+    let cache_max_age = ctx.state.cache.as_ref().map(|cache| cache.ttl().as_secs());
+    let mut image = Image::new(
+        payload,
+        upstream_headers,
+        ctx.desired_geometry,
+        ctx.state.config.engine.clone(),
+        cache_max_age,
+        ctx.video_seek_secs,
+        ctx.encode_options,
+    )?;
     // Resize/Convert image and send back to client
-    if let Some(target_mime) = get_target_mime(request_parts.headers.get(ACCEPT).cloned()) {
+    if let Some(target_mime) = ctx.target_override {
         image.set_mime(target_mime);
     }
-    let mut response = Response::new(Body::from(image.save()?));
-    let response_headers = response.headers_mut();
-    *response_headers = image.get_headers();
-    Ok(response)
+    let body = image.save()?;
+    let headers = image.get_headers();
+
+    if let (Some(key), Some(cache)) = (ctx.cache_key, &ctx.state.cache) {
+        cache.insert(key, CacheEntry::new(body.clone(), headers.clone(), validators));
+    }
+
+    Ok((body, headers))
+}
+
+fn entry_to_response(entry: CacheEntry) -> Response {
+    let mut response = Response::new(Body::from(entry.body));
+    *response.headers_mut() = entry.headers;
+    response
+}
+
+fn outcome_to_response(body: &Arc<Vec<u8>>, headers: HeaderMap) -> Response {
+    let mut response = Response::new(Body::from(body.as_ref().clone()));
+    *response.headers_mut() = headers;
+    response
+}
+
+fn add_revalidation_headers(headers: &mut reqwest::header::HeaderMap, validators: &Validators) {
+    if let Some(etag) = &validators.etag {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(etag) {
+            headers.insert(reqwest::header::IF_NONE_MATCH, value);
+        }
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(last_modified) {
+            headers.insert(reqwest::header::IF_MODIFIED_SINCE, value);
+        }
+    }
 }
 
 fn create_http_client() -> anyhow::Result<reqwest::Client> {
@@ -102,13 +264,13 @@ fn create_http_client() -> anyhow::Result<reqwest::Client> {
         .build()?)
 }
 
-fn get_target_mime(accept: Option<HeaderValue>) -> Option<ImageFormat> {
+fn get_target_mime(accept: Option<HeaderValue>, options: &EncodeOptions) -> Option<ImageFormat> {
     if let Some(accept_header) = accept {
         let accept_str = accept_header.to_str().unwrap_or_default();
-        if accept_str.contains("image/avif") {
+        if accept_str.contains("image/avif") && options.is_format_allowed("image/avif") {
             debug!("The client accepts AVIF");
             return Some(ImageFormat::Avif);
-        } else if accept_str.contains("image/webp") {
+        } else if accept_str.contains("image/webp") && options.is_format_allowed("image/webp") {
             debug!("The client accepts WebP");
             return Some(ImageFormat::WebP);
         }
@@ -147,7 +309,10 @@ fn extract_geometry(uri_string: String) -> Option<(u32, u32)> {
     Some((width, height))
 }
 
-fn get_upstream(uri: &str, map: Vec<Upstreams>) -> anyhow::Result<Option<String>> {
+fn get_upstream(
+    uri: &str,
+    map: Vec<Upstreams>,
+) -> anyhow::Result<Option<(String, Option<f64>, EncodeOptions)>> {
     for entry in map {
         debug!("Trying RE {} against {}", &entry.path, uri);
         let re = Regex::new(&entry.path)?;
@@ -165,7 +330,7 @@ fn get_upstream(uri: &str, map: Vec<Upstreams>) -> anyhow::Result<Option<String>
             let url: Vec<String> =
                 zipped.into_iter().flat_map(|(a, b)| vec![a, b.to_string()]).collect();
             debug!("Upstream URL: {}", url.join(""));
-            return Ok(Some(url.join("")));
+            return Ok(Some((url.join(""), entry.video_seek_secs, entry.encode)));
         }
     }
     Ok(None)
@@ -1,6 +1,7 @@
 #![forbid(unsafe_code)]
+use crate::config::{EncodeOptions, ResizeFilter};
 use anyhow::{anyhow, Result};
-use axum::http::header::{HeaderMap, HeaderValue, CONTENT_TYPE, VARY};
+use axum::http::header::{HeaderMap, HeaderValue, CACHE_CONTROL, CONTENT_TYPE, VARY};
 use bytes::Bytes;
 use image::{
     codecs::png::FilterType as PngFilterType, imageops::FilterType, load_from_memory_with_format,
@@ -10,16 +11,31 @@ use libvips::{ops, VipsImage};
 use serde::Deserialize;
 
 const DEFAULT_GEOMETRY: (u32, u32) = (800, 800);
+const DEFAULT_RESIZE_FILTER: FilterType = FilterType::Triangle;
 
+mod ffmpeg;
 mod image_rs;
 mod vips;
 
+use ffmpeg::{ffmpeg_decode, ffmpeg_encode, Media};
 use image_rs::imagers_decode;
 use vips::vips_decode;
 
+impl From<ResizeFilter> for FilterType {
+    fn from(filter: ResizeFilter) -> Self {
+        match filter {
+            ResizeFilter::Nearest => FilterType::Nearest,
+            ResizeFilter::Triangle => FilterType::Triangle,
+            ResizeFilter::CatmullRom => FilterType::CatmullRom,
+            ResizeFilter::Gaussian => FilterType::Gaussian,
+            ResizeFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
+}
+
 trait ImageProcessing {
-    fn resize(&self, width: u32, height: u32) -> Result<Engine>;
-    fn encode(&self, format: ImageFormat) -> Result<Vec<u8>>;
+    fn resize(&self, width: u32, height: u32, options: &EncodeOptions) -> Result<Engine>;
+    fn encode(&self, format: ImageFormat, options: &EncodeOptions) -> Result<Vec<u8>>;
     fn get_geometry(&self) -> (u32, u32);
 }
 
@@ -28,18 +44,19 @@ pub enum EngineType {
     #[default]
     ImageRs,
     Vips,
+    Ffmpeg,
 }
 #[derive(Debug, Clone)]
 pub enum Engine {
     ImageRs(DynamicImage),
     Vips(VipsImage),
+    Ffmpeg(Media),
 }
 impl ImageProcessing for Engine {
-    fn resize(&self, width: u32, height: u32) -> Result<Engine> {
+    fn resize(&self, width: u32, height: u32, options: &EncodeOptions) -> Result<Engine> {
+        let filter: FilterType = options.resize_filter.map(Into::into).unwrap_or(DEFAULT_RESIZE_FILTER);
         match self {
-            Engine::ImageRs(d) => {
-                Ok(Engine::ImageRs(d.resize(width, height, FilterType::Triangle)))
-            }
+            Engine::ImageRs(d) => Ok(Engine::ImageRs(d.resize(width, height, filter))),
             Engine::Vips(v) => {
                 let width_ratio = width as f64 / v.get_width() as f64;
                 let height_ratio = height as f64 / v.get_height() as f64;
@@ -51,26 +68,46 @@ impl ImageProcessing for Engine {
                 let data = ops::resize(v, ratio)?;
                 Ok(Engine::Vips(data))
             }
+            Engine::Ffmpeg(media) => {
+                debug!("Resizing {} frame(s) to {width}x{height}", media.frames.len());
+                let frames = media
+                    .frames
+                    .iter()
+                    .map(|f| ffmpeg::Frame { image: f.image.resize(width, height, filter), delay: f.delay })
+                    .collect();
+                Ok(Engine::Ffmpeg(Media { frames }))
+            }
         }
     }
-    fn encode(&self, format: ImageFormat) -> Result<Vec<u8>> {
+    fn encode(&self, format: ImageFormat, options: &EncodeOptions) -> Result<Vec<u8>> {
         match self {
-            Engine::ImageRs(d) => imagers_decode(d, format),
-            Engine::Vips(v) => vips_decode(v, format),
+            Engine::ImageRs(d) => imagers_decode(d, format, options),
+            Engine::Vips(v) => vips_decode(v, format, options),
+            Engine::Ffmpeg(media) => ffmpeg_encode(media, format, options),
         }
     }
     fn get_geometry(&self) -> (u32, u32) {
         match self {
             Engine::ImageRs(d) => (d.width(), d.height()),
             Engine::Vips(v) => (v.get_width() as u32, v.get_height() as u32),
+            Engine::Ffmpeg(media) => media.geometry(),
         }
     }
 }
 
+impl Engine {
+    /// True for a multi-frame `Ffmpeg` decode (animated GIF/WebP source).
+    /// Other engines don't carry multiple frames.
+    fn is_animated(&self) -> bool {
+        matches!(self, Engine::Ffmpeg(media) if media.is_animated())
+    }
+}
+
 pub struct Image {
     data: Engine,
     mime: ImageFormat,
     headers: HeaderMap,
+    encode_options: EncodeOptions,
 }
 impl Image {
     pub fn new(
@@ -78,22 +115,41 @@ impl Image {
         mut upstream_headers: HeaderMap,
         geometry: Option<(u32, u32)>,
         engine: EngineType,
+        cache_max_age: Option<u64>,
+        video_seek_secs: Option<f64>,
+        encode_options: EncodeOptions,
     ) -> Result<Self> {
-        let mime = if let Some(content_type) = upstream_headers.get(CONTENT_TYPE) {
-            match ImageFormat::from_mime_type(content_type.to_str().unwrap_or_default()) {
-                Some(m) => m,
-                None => return Err(anyhow!("Could not parse mime type")),
+        let content_type = upstream_headers
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("Mime not parseable"))?;
+        let source_format = ImageFormat::from_mime_type(&content_type);
+        // ffmpeg is the only engine that can thumbnail video, whose content type
+        // (e.g. `video/mp4`) the `image` crate doesn't recognise as an ImageFormat.
+        // Everything else requires a recognisable still-image mime up front.
+        let mime = match (source_format, &engine) {
+            (Some(fmt), _) => fmt,
+            (None, EngineType::Ffmpeg) => ImageFormat::Jpeg,
+            (None, _) => {
+                error!("Could not parse mime type");
+                return Err(anyhow!("Mime not parseable"));
             }
-        } else {
-            error!("Could not parse mime type");
-            return Err(anyhow!("Mime not parseable"));
         };
         debug!("Mime: {}", mime.to_mime_type());
-        //headers.append(CACHE_CONTROL, HeaderValue::from_static("public"));
+        if let Some(max_age) = cache_max_age {
+            upstream_headers.insert(
+                CACHE_CONTROL,
+                HeaderValue::from_str(&format!("public, max-age={max_age}"))?,
+            );
+        }
         upstream_headers.append(VARY, HeaderValue::from_static("Accept"));
 
         debug!("Loading image");
-        let raw_data = import_image(bytes, engine, mime)?;
+        let raw_data = {
+            let _span = tracing::info_span!("decode", mime = mime.to_mime_type()).entered();
+            import_image(bytes, engine, source_format, video_seek_secs)?
+        };
         let (nwidth, nheight) = if let Some(ngeometry) = geometry {
             debug!("Desired geometry: {}x{}", ngeometry.0, ngeometry.1);
             (ngeometry.0, ngeometry.1)
@@ -106,31 +162,78 @@ impl Image {
             raw_data
         } else {
             debug!("Resizing to {nwidth}x{nheight}");
-            raw_data.resize(nwidth, nheight)?
+            let _span = tracing::info_span!(
+                "resize",
+                source.width = w,
+                source.height = h,
+                target.width = nwidth,
+                target.height = nheight
+            )
+            .entered();
+            raw_data.resize(nwidth, nheight, &encode_options)?
         };
-        Ok(Image { data, mime, headers: upstream_headers })
+        Ok(Image { data, mime, headers: upstream_headers, encode_options })
     }
 
     pub fn get_headers(&self) -> HeaderMap {
         self.headers.clone()
     }
 
+    /// Overrides the target encode mime, e.g. after `Accept` negotiation. A
+    /// no-op when the decoded source is animated and `mime` isn't `Gif`: the
+    /// ffmpeg encode path has no animated writer for any other format, so
+    /// applying the override would collapse the output to a single frame for
+    /// ordinary browser traffic (which negotiates AVIF/WebP).
     pub fn set_mime(&mut self, mime: ImageFormat) {
+        if self.data.is_animated() && mime != ImageFormat::Gif {
+            debug!(
+                "Keeping animated source as {} rather than negotiated {}",
+                self.mime.to_mime_type(),
+                mime.to_mime_type()
+            );
+            return;
+        }
         self.mime = mime;
         self.headers
             .insert(CONTENT_TYPE, HeaderValue::from_static(ImageFormat::to_mime_type(&mime)));
     }
 
     pub fn save(&mut self) -> Result<Vec<u8>> {
-        self.data.encode(self.mime)
+        // `allowed_formats` also has to gate the source/original mime, not just
+        // the AVIF/WebP up-negotiation in `get_target_mime`: that function never
+        // sees (and can't reject) a disallowed source format it didn't choose.
+        if !self.encode_options.is_format_allowed(self.mime.to_mime_type()) {
+            return Err(anyhow!(
+                "Target format {} is not in this route's allowed_formats",
+                self.mime.to_mime_type()
+            ));
+        }
+        let span = tracing::info_span!(
+            "encode",
+            target.mime = self.mime.to_mime_type(),
+            output.bytes = tracing::field::Empty
+        );
+        let _guard = span.clone().entered();
+        let encoded = self.data.encode(self.mime, &self.encode_options)?;
+        span.record("output.bytes", encoded.len());
+        Ok(encoded)
     }
 }
 
-fn import_image(data: Bytes, engine: EngineType, format: ImageFormat) -> Result<Engine> {
+fn import_image(
+    data: Bytes,
+    engine: EngineType,
+    source_format: Option<ImageFormat>,
+    video_seek_secs: Option<f64>,
+) -> Result<Engine> {
     match engine {
         EngineType::ImageRs => {
+            let format = source_format.ok_or_else(|| anyhow!("Could not parse mime type"))?;
             Ok(Engine::ImageRs(load_from_memory_with_format(data.as_ref(), format)?))
         }
         EngineType::Vips => Ok(Engine::Vips(VipsImage::new_from_buffer(&data[..], "")?)),
+        EngineType::Ffmpeg => {
+            Ok(Engine::Ffmpeg(ffmpeg_decode(&data, source_format, video_seek_secs)?))
+        }
     }
 }
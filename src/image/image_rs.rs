@@ -1,4 +1,5 @@
 #![forbid(unsafe_code)]
+use crate::config::EncodeOptions;
 use crate::image::PngFilterType;
 use anyhow::anyhow;
 use anyhow::Result;
@@ -12,12 +13,20 @@ use image::{
     DynamicImage, ImageFormat,
 };
 
-pub fn imagers_decode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u8>> {
+const DEFAULT_JPEG_QUALITY: u8 = 95;
+const DEFAULT_AVIF_SPEED: u8 = 10;
+const DEFAULT_AVIF_QUALITY: u8 = 95;
+
+pub fn imagers_decode(
+    image: &DynamicImage,
+    format: ImageFormat,
+    options: &EncodeOptions,
+) -> Result<Vec<u8>> {
     match format {
-        ImageFormat::Jpeg => encode_jpeg(image),
-        ImageFormat::Png => encode_png(image),
-        ImageFormat::WebP => encode_webp(image),
-        ImageFormat::Avif => encode_avif(image),
+        ImageFormat::Jpeg => encode_jpeg(image, options),
+        ImageFormat::Png => encode_png(image, options),
+        ImageFormat::WebP => encode_webp(image, options),
+        ImageFormat::Avif => encode_avif(image, options),
         _ => {
             error!("Got unsupported image format: {}", format.to_mime_type());
             Err(anyhow!("unsupported format"))
@@ -25,41 +34,50 @@ pub fn imagers_decode(image: &DynamicImage, format: ImageFormat) -> Result<Vec<u
     }
 }
 
-fn encode_jpeg(image: &DynamicImage) -> Result<Vec<u8>> {
+fn encode_jpeg(image: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as JPEG");
     let mut buffer: Vec<u8> = Vec::new();
-    let encoder = JpegEncoder::new_with_quality(&mut buffer, 95);
+    let quality = options.jpeg_quality.unwrap_or(DEFAULT_JPEG_QUALITY);
+    let encoder = JpegEncoder::new_with_quality(&mut buffer, quality);
     image.write_with_encoder(encoder)?;
     debug!("Saved");
     Ok(buffer)
 }
 
-fn encode_png(image: &DynamicImage) -> Result<Vec<u8>> {
+fn encode_png(image: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as PNG");
     let mut buffer: Vec<u8> = Vec::new();
-    let encoder = PngEncoder::new_with_quality(
-        &mut buffer,
-        CompressionType::Default,
-        PngFilterType::NoFilter,
-    );
+    // `image`'s PNG encoder only exposes a coarse compression tier, so map the
+    // configured 0-9 zlib-style level onto it.
+    let compression = match options.png_compression_level.unwrap_or(6) {
+        0..=2 => CompressionType::Fast,
+        7..=9 => CompressionType::Best,
+        _ => CompressionType::Default,
+    };
+    let encoder = PngEncoder::new_with_quality(&mut buffer, compression, PngFilterType::NoFilter);
     image.write_with_encoder(encoder)?;
     debug!("Saved");
     Ok(buffer)
 }
 
-fn encode_webp(image: &DynamicImage) -> Result<Vec<u8>> {
+fn encode_webp(image: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as WEBP");
     let mut buffer: Vec<u8> = Vec::new();
+    if options.webp_lossless == Some(false) {
+        debug!("Lossy WebP requested, but image-rs only encodes lossless WebP; ignoring");
+    }
     let encoder = WebPEncoder::new_lossless(&mut buffer);
     image.write_with_encoder(encoder)?;
     debug!("Saved");
     Ok(buffer)
 }
 
-fn encode_avif(image: &DynamicImage) -> Result<Vec<u8>> {
+fn encode_avif(image: &DynamicImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as Avif");
     let mut buffer: Vec<u8> = Vec::new();
-    let encoder = AvifEncoder::new_with_speed_quality(&mut buffer, 10, 95);
+    let speed = options.avif_speed.unwrap_or(DEFAULT_AVIF_SPEED);
+    let quality = options.avif_quality.unwrap_or(DEFAULT_AVIF_QUALITY);
+    let encoder = AvifEncoder::new_with_speed_quality(&mut buffer, speed, quality);
     image.write_with_encoder(encoder)?;
     debug!("Saved");
     Ok(buffer)
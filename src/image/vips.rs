@@ -1,14 +1,20 @@
 #![forbid(unsafe_code)]
+use crate::config::EncodeOptions;
 use anyhow::{anyhow, Result};
 use image::ImageFormat;
 use libvips::{ops::ForeignHeifCompression, VipsImage};
 
-pub fn vips_decode(image: &VipsImage, format: ImageFormat) -> Result<Vec<u8>> {
+const DEFAULT_JPEG_QUALITY: i32 = 90;
+const DEFAULT_PNG_COMPRESSION: i32 = 6;
+const DEFAULT_AVIF_QUALITY: i32 = 95;
+const DEFAULT_WEBP_LOSSLESS: bool = true;
+
+pub fn vips_decode(image: &VipsImage, format: ImageFormat, options: &EncodeOptions) -> Result<Vec<u8>> {
     match format {
-        ImageFormat::Jpeg => save_jpeg(image),
-        ImageFormat::Png => save_png(image),
-        ImageFormat::WebP => save_webp(image),
-        ImageFormat::Avif => save_avif(image),
+        ImageFormat::Jpeg => save_jpeg(image, options),
+        ImageFormat::Png => save_png(image, options),
+        ImageFormat::WebP => save_webp(image, options),
+        ImageFormat::Avif => save_avif(image, options),
         _ => {
             error!("Got unsupported image format: {}", format.to_mime_type());
             Err(anyhow!("unsupported format"))
@@ -16,47 +22,55 @@ pub fn vips_decode(image: &VipsImage, format: ImageFormat) -> Result<Vec<u8>> {
     }
 }
 
-fn save_jpeg(image: &VipsImage) -> Result<Vec<u8>> {
+fn save_jpeg(image: &VipsImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as JPEG");
-    let options = libvips::ops::JpegsaveBufferOptions {
-        q: 90,
+    let q = options.jpeg_quality.map(i32::from).unwrap_or(DEFAULT_JPEG_QUALITY);
+    let save_options = libvips::ops::JpegsaveBufferOptions {
+        q,
         background: vec![255.],
         optimize_coding: true,
         interlace: true,
         ..libvips::ops::JpegsaveBufferOptions::default()
     };
 
-    Ok(libvips::ops::jpegsave_buffer_with_opts(image, &options)?)
+    Ok(libvips::ops::jpegsave_buffer_with_opts(image, &save_options)?)
 }
 
-fn save_png(image: &VipsImage) -> Result<Vec<u8>> {
+fn save_png(image: &VipsImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as PNG");
-    let options = libvips::ops::PngsaveBufferOptions {
-        q: 90,
+    // vips' PNG `q` is palette quantization quality (only used when `palette:
+    // true`); the zlib compression effort (0-9) is the separate `compression`
+    // field, which is what `png_compression_level` is meant to drive.
+    let compression = options.png_compression_level.map(i32::from).unwrap_or(DEFAULT_PNG_COMPRESSION).clamp(0, 9);
+    let save_options = libvips::ops::PngsaveBufferOptions {
+        compression,
         background: vec![255.],
         interlace: true,
         bitdepth: 8,
         ..libvips::ops::PngsaveBufferOptions::default()
     };
-    Ok(libvips::ops::pngsave_buffer_with_opts(image, &options)?)
+    Ok(libvips::ops::pngsave_buffer_with_opts(image, &save_options)?)
 }
 
-fn save_webp(image: &VipsImage) -> Result<Vec<u8>> {
+fn save_webp(image: &VipsImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as WEBP");
-    let options = libvips::ops::WebpsaveBufferOptions {
+    let save_options = libvips::ops::WebpsaveBufferOptions {
         q: 90,
         background: vec![255.],
+        lossless: options.webp_lossless.unwrap_or(DEFAULT_WEBP_LOSSLESS),
         ..libvips::ops::WebpsaveBufferOptions::default()
     };
-    Ok(libvips::ops::webpsave_buffer_with_opts(image, &options)?)
+    Ok(libvips::ops::webpsave_buffer_with_opts(image, &save_options)?)
 }
 
-fn save_avif(image: &VipsImage) -> Result<Vec<u8>> {
+fn save_avif(image: &VipsImage, options: &EncodeOptions) -> Result<Vec<u8>> {
     debug!("Saving as Avif");
-    let options = libvips::ops::HeifsaveBufferOptions {
+    let q = options.avif_quality.map(i32::from).unwrap_or(DEFAULT_AVIF_QUALITY);
+    let save_options = libvips::ops::HeifsaveBufferOptions {
+        q,
         background: vec![255.],
         compression: ForeignHeifCompression::Av1,
         ..Default::default()
     };
-    Ok(libvips::ops::heifsave_buffer_with_opts(image, &options)?)
+    Ok(libvips::ops::heifsave_buffer_with_opts(image, &save_options)?)
 }
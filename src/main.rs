@@ -2,9 +2,12 @@
 #[macro_use]
 extern crate log;
 
+mod cache;
 mod config;
 mod http;
 mod image;
+mod singleflight;
+mod telemetry;
 
 use crate::config::Config;
 use crate::http::http_server;
@@ -12,7 +15,6 @@ use crate::image::EngineType;
 
 use anyhow::Result;
 use clap::Parser;
-use env_logger::Env;
 use libvips::VipsApp;
 use std::ffi::OsString;
 
@@ -23,16 +25,13 @@ struct CmdOpts {
 }
 
 fn main() {
-    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
-        .format_timestamp_millis()
-        .init();
     let cmdopts = CmdOpts::parse();
     match run(&cmdopts) {
         Ok(()) => {
             std::process::exit(0);
         }
         Err(e) => {
-            error!("{e}");
+            eprintln!("{e}");
             std::process::exit(1);
         }
     };
@@ -53,6 +52,10 @@ fn run(cmdopts: &CmdOpts) -> Result<()> {
             tokio::runtime::Builder::new_multi_thread().worker_threads(n).enable_all().build()?
         }
     };
+    // `telemetry::init` spawns the OTLP batch exporter onto the Tokio runtime, so it
+    // must run with the runtime entered rather than before the runtime exists.
+    let _guard = runtime.enter();
+    telemetry::init(&config)?;
     runtime.block_on(http_server(config))?;
     Ok(())
 }
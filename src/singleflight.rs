@@ -0,0 +1,90 @@
+#![forbid(unsafe_code)]
+use crate::cache::CacheKey;
+use axum::http::HeaderMap;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::broadcast;
+
+/// What a leader broadcasts to any followers waiting on the same key.
+#[derive(Clone)]
+pub enum FlightOutcome {
+    Success { body: Arc<Vec<u8>>, headers: HeaderMap },
+    Error(Arc<str>),
+}
+
+enum JoinResult {
+    Leader,
+    Follower(broadcast::Receiver<FlightOutcome>),
+}
+
+/// Coalesces concurrent requests for the same `(upstream URL, geometry, target
+/// MIME)` key into a single fetch+resize+encode: the first caller to `join` a
+/// key performs the work, the rest await its broadcast result.
+#[derive(Default)]
+pub struct SingleFlight {
+    inflight: Mutex<HashMap<CacheKey, broadcast::Sender<FlightOutcome>>>,
+}
+
+/// Returned to the caller that must do the work. Dropping it without calling
+/// `complete` (e.g. an early `?` return) still clears the key and wakes any
+/// followers with an error, so a leader failure never hangs them.
+pub struct LeaderGuard<'a> {
+    flight: &'a SingleFlight,
+    key: Option<CacheKey>,
+}
+
+impl LeaderGuard<'_> {
+    pub fn complete(mut self, outcome: FlightOutcome) {
+        if let Some(key) = self.key.take() {
+            self.flight.finish(&key, outcome);
+        }
+    }
+}
+
+impl Drop for LeaderGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.flight.finish(&key, FlightOutcome::Error(Arc::from("leader dropped without a result")));
+        }
+    }
+}
+
+pub enum Lead<'a> {
+    Leader(LeaderGuard<'a>),
+    Follower(broadcast::Receiver<FlightOutcome>),
+}
+
+impl SingleFlight {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers interest in `key`. The first caller becomes the leader and
+    /// must eventually call `LeaderGuard::complete`; later callers become
+    /// followers and receive the leader's broadcast result.
+    pub fn join(&self, key: CacheKey) -> Lead<'_> {
+        match self.try_join(key.clone()) {
+            JoinResult::Leader => Lead::Leader(LeaderGuard { flight: self, key: Some(key) }),
+            JoinResult::Follower(rx) => Lead::Follower(rx),
+        }
+    }
+
+    fn try_join(&self, key: CacheKey) -> JoinResult {
+        let mut inflight = self.inflight.lock().unwrap();
+        if let Some(sender) = inflight.get(&key) {
+            return JoinResult::Follower(sender.subscribe());
+        }
+        let (sender, _) = broadcast::channel(1);
+        inflight.insert(key, sender);
+        JoinResult::Leader
+    }
+
+    fn finish(&self, key: &CacheKey, outcome: FlightOutcome) {
+        if let Some(sender) = self.inflight.lock().unwrap().remove(key) {
+            // Err means every follower already gave up; nothing left to wake.
+            let _ = sender.send(outcome);
+        }
+    }
+}
@@ -0,0 +1,84 @@
+#![forbid(unsafe_code)]
+use crate::config::Config;
+use anyhow::Result;
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+
+const DEFAULT_SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// Installs the global `tracing` subscriber, bridging the existing `log` macros and,
+/// when configured, exporting spans to an OTLP collector.
+pub fn init(config: &Config) -> Result<()> {
+    tracing_log::LogTracer::init()?;
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let registry = tracing_subscriber::registry().with(filter).with(tracing_subscriber::fmt::layer());
+
+    let endpoint = config.tracing.as_ref().and_then(|t| t.otlp_endpoint.clone());
+    let Some(endpoint) = endpoint else {
+        registry.try_init()?;
+        return Ok(());
+    };
+
+    let service_name = config
+        .tracing
+        .as_ref()
+        .and_then(|t| t.service_name.clone())
+        .unwrap_or_else(|| DEFAULT_SERVICE_NAME.to_string());
+
+    let provider = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(&endpoint))
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![KeyValue::new("service.name", service_name)]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)?;
+    let tracer = provider.tracer(DEFAULT_SERVICE_NAME);
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+
+    registry.with(tracing_opentelemetry::layer().with_tracer(tracer)).try_init()?;
+    info!("OTLP tracing exporter initialized, endpoint={endpoint}");
+    Ok(())
+}
+
+/// Extracts a W3C `traceparent`/`tracestate` context from inbound axum headers.
+pub fn extract_parent_context(headers: &axum::http::HeaderMap) -> opentelemetry::Context {
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&AxumHeaderCarrier(headers))
+    })
+}
+
+/// Injects the current span's trace context into outbound reqwest headers so the
+/// upstream can join the trace.
+pub fn inject_context(headers: &mut reqwest::header::HeaderMap) {
+    let cx = tracing::Span::current().context();
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&cx, &mut ReqwestHeaderCarrier(headers));
+    });
+}
+
+struct AxumHeaderCarrier<'a>(&'a axum::http::HeaderMap);
+impl Extractor for AxumHeaderCarrier<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+struct ReqwestHeaderCarrier<'a>(&'a mut reqwest::header::HeaderMap);
+impl Injector for ReqwestHeaderCarrier<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(val)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, val);
+        }
+    }
+}